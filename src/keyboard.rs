@@ -0,0 +1,132 @@
+//! Types related to the keyboard.
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// Represents the current state of the keyboard modifiers.
+    ///
+    /// Each flag represents a modifier and is set if this modifier is active.
+    #[derive(Default)]
+    pub struct ModifiersState: u64 {
+        /// The "shift" key.
+        const SHIFT = 0b100;
+        /// The "control" key.
+        const CONTROL = 0b100 << 3;
+        /// The "alt" key.
+        const ALT = 0b100 << 6;
+        /// This is the "windows" key on PC and "command" key on Mac.
+        const SUPER = 0b100 << 9;
+        /// The "caps lock" toggle state.
+        ///
+        /// Unlike the other modifiers, this reflects whether caps lock is
+        /// currently *engaged* rather than whether a key is physically held
+        /// down.
+        const CAPS_LOCK = 0b100 << 12;
+        /// The "num lock" toggle state.
+        ///
+        /// Like [`CAPS_LOCK`](Self::CAPS_LOCK), this reflects the toggle
+        /// state rather than whether a key is held.
+        const NUM_LOCK = 0b100 << 15;
+        /// The "hyper" key, present on some keyboards as an additional
+        /// modifier alongside shift/control/alt/super.
+        const HYPER = 0b100 << 18;
+
+        /// The left "shift" key.
+        const LEFT_SHIFT = 0b100 << 21;
+        /// The right "shift" key.
+        const RIGHT_SHIFT = 0b100 << 24;
+        /// The left "control" key.
+        const LEFT_CONTROL = 0b100 << 27;
+        /// The right "control" key.
+        const RIGHT_CONTROL = 0b100 << 30;
+        /// The left "alt" key.
+        const LEFT_ALT = 0b100 << 33;
+        /// The right "alt" key.
+        const RIGHT_ALT = 0b100 << 36;
+        /// The left "super" key.
+        const LEFT_SUPER = 0b100 << 39;
+        /// The right "super" key.
+        const RIGHT_SUPER = 0b100 << 42;
+
+        /// Deprecated alias for [`SUPER`](Self::SUPER).
+        #[deprecated(note = "Use `SUPER` instead.")]
+        const META = Self::SUPER.bits;
+    }
+}
+
+impl ModifiersState {
+    /// Returns `true` if the shift key is pressed.
+    pub fn shift_key(&self) -> bool {
+        self.intersects(Self::SHIFT)
+    }
+
+    /// Returns `true` if the control key is pressed.
+    pub fn control_key(&self) -> bool {
+        self.intersects(Self::CONTROL)
+    }
+
+    /// Returns `true` if the alt key is pressed.
+    pub fn alt_key(&self) -> bool {
+        self.intersects(Self::ALT)
+    }
+
+    /// Returns `true` if the super key is pressed.
+    pub fn super_key(&self) -> bool {
+        self.intersects(Self::SUPER)
+    }
+
+    /// Returns `true` if caps lock is currently engaged.
+    pub fn caps_lock_key(&self) -> bool {
+        self.intersects(Self::CAPS_LOCK)
+    }
+
+    /// Returns `true` if num lock is currently engaged.
+    pub fn num_lock_key(&self) -> bool {
+        self.intersects(Self::NUM_LOCK)
+    }
+
+    /// Returns `true` if the hyper key is pressed.
+    pub fn hyper_key(&self) -> bool {
+        self.intersects(Self::HYPER)
+    }
+
+    /// Returns `true` if the left shift key is pressed.
+    pub fn left_shift_key(&self) -> bool {
+        self.intersects(Self::LEFT_SHIFT)
+    }
+
+    /// Returns `true` if the right shift key is pressed.
+    pub fn right_shift_key(&self) -> bool {
+        self.intersects(Self::RIGHT_SHIFT)
+    }
+
+    /// Returns `true` if the left control key is pressed.
+    pub fn left_control_key(&self) -> bool {
+        self.intersects(Self::LEFT_CONTROL)
+    }
+
+    /// Returns `true` if the right control key is pressed.
+    pub fn right_control_key(&self) -> bool {
+        self.intersects(Self::RIGHT_CONTROL)
+    }
+
+    /// Returns `true` if the left alt key is pressed.
+    pub fn left_alt_key(&self) -> bool {
+        self.intersects(Self::LEFT_ALT)
+    }
+
+    /// Returns `true` if the right alt key is pressed.
+    pub fn right_alt_key(&self) -> bool {
+        self.intersects(Self::RIGHT_ALT)
+    }
+
+    /// Returns `true` if the left super key is pressed.
+    pub fn left_super_key(&self) -> bool {
+        self.intersects(Self::LEFT_SUPER)
+    }
+
+    /// Returns `true` if the right super key is pressed.
+    pub fn right_super_key(&self) -> bool {
+        self.intersects(Self::RIGHT_SUPER)
+    }
+}
@@ -0,0 +1,476 @@
+use std::{collections::HashSet, convert::TryFrom, os::unix::io::RawFd};
+
+use memmap2::MmapOptions;
+use sctk::reexports::client::protocol::wl_keyboard::KeymapFormat;
+use xkbcommon::xkb;
+
+use crate::keyboard::{Key, KeyCode, KeyLocation, ModifiersState, NativeKeyCode};
+
+/// Drives keyboard layout resolution for Wayland.
+///
+/// Unlike X11 and Windows, which both query the system for the active
+/// layout themselves, Wayland hands the compositor's keymap straight to the
+/// client as a shared-memory file descriptor on `wl_keyboard::keymap`, so
+/// this wraps xkbcommon - the library the compositor itself is built on -
+/// to turn that keymap into the same `Key`/`KeyCode`/`ModifiersState`
+/// translations the other backends produce.
+pub struct WaylandKeyboard {
+    context: xkb::Context,
+    keymap: Option<xkb::Keymap>,
+    state: Option<xkb::State>,
+    /// Evdev keycodes of every key currently held down, fed in via
+    /// `track_key` as `wl_keyboard::key` events arrive; `update_modifiers`
+    /// picks the side-aware modifier keys (Shift/Ctrl/Alt/Super) out of
+    /// this set by their keysyms.
+    ///
+    /// XKB's modifier state is an effective bitmask with no memory of which
+    /// physical key set each bit, so - unlike X11, where `ModifierKeymap`
+    /// can resolve a keycode's side straight from its keysym on demand -
+    /// producing `LEFT_*`/`RIGHT_*` flags here means tracking the held
+    /// keycodes ourselves and resolving each one's side through its keysym
+    /// below - the modifier keys' own keysyms (`Shift_L`/`Shift_R`, ...)
+    /// are fixed regardless of layout or other held modifiers, unlike the
+    /// keysyms `evdev_code_to_key_code`/`evdev_code_to_location` are built
+    /// to avoid relying on for the rest of the keymap.
+    held_side_keys: HashSet<u32>,
+    /// Leaked, deduplicated key label strings, following the same
+    /// leak-and-dedup pattern the Windows layout cache uses for
+    /// `Key::Character`, since both need a `&'static str` to hand back
+    /// without re-allocating on every lookup.
+    strings: HashSet<&'static str>,
+}
+
+impl WaylandKeyboard {
+    pub fn new() -> Self {
+        WaylandKeyboard {
+            context: xkb::Context::new(xkb::CONTEXT_NO_FLAGS),
+            keymap: None,
+            state: None,
+            held_side_keys: HashSet::new(),
+            strings: HashSet::new(),
+        }
+    }
+
+    /// Handles a `wl_keyboard::keymap` event.
+    ///
+    /// Only the `XkbV1` format is understood; any other format (in
+    /// practice only `NoKeymap`, sent for seats without a keyboard mapping)
+    /// is ignored and the previously loaded keymap, if any, is left in
+    /// place.
+    ///
+    /// `fd` is owned by the caller (as handed to us by the Wayland
+    /// protocol dispatch) - we only borrow it for the duration of this
+    /// call, mapping it read-only since we only need to read the keymap
+    /// text out of it.
+    pub fn set_keymap_from_fd(&mut self, format: KeymapFormat, fd: RawFd, size: u32) {
+        if format != KeymapFormat::XkbV1 {
+            return;
+        }
+
+        let size = match usize::try_from(size) {
+            Ok(size) => size,
+            Err(_) => return,
+        };
+
+        let map = match unsafe { MmapOptions::new().len(size).map_copy_read_only(fd) } {
+            Ok(map) => map,
+            Err(_) => return,
+        };
+
+        // The keymap text the compositor hands us is NUL-terminated; strip
+        // that before handing it to xkbcommon as a string.
+        let keymap_text = match std::str::from_utf8(&map) {
+            Ok(text) => text.trim_end_matches('\0'),
+            Err(_) => return,
+        };
+
+        let keymap = match xkb::Keymap::new_from_string(
+            &self.context,
+            keymap_text,
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        ) {
+            Some(keymap) => keymap,
+            // Not a keymap xkbcommon understands; keep whatever we had.
+            None => return,
+        };
+
+        self.state = Some(xkb::State::new(&keymap));
+        self.keymap = Some(keymap);
+    }
+
+    /// Handles a `wl_keyboard::key` event, recording whether a side-aware
+    /// modifier key is currently held so `update_modifiers` can derive
+    /// `LEFT_*`/`RIGHT_*` flags from it. `keycode` is the evdev keycode, as
+    /// delivered by the event; `pressed` is `true` for `Pressed`, `false`
+    /// for `Released`.
+    pub fn track_key(&mut self, keycode: u32, pressed: bool) {
+        if pressed {
+            self.held_side_keys.insert(keycode);
+        } else {
+            self.held_side_keys.remove(&keycode);
+        }
+    }
+
+    /// Handles a `wl_keyboard::modifiers` event, applying the serialized
+    /// depressed/latched/locked modifier masks and active group to our xkb
+    /// state, then returning the resulting cross-platform `ModifiersState`.
+    ///
+    /// Returns `None` if no keymap has been loaded yet - the compositor is
+    /// expected to always send `keymap` before the first `modifiers` event,
+    /// but we don't want to panic if it doesn't.
+    pub fn update_modifiers(
+        &mut self,
+        mods_depressed: u32,
+        mods_latched: u32,
+        mods_locked: u32,
+        group: u32,
+    ) -> Option<ModifiersState> {
+        let keymap = self.keymap.as_ref()?;
+        let state = self.state.as_mut()?;
+
+        state.update_mask(mods_depressed, mods_latched, mods_locked, 0, 0, group);
+
+        let is_active = |name| state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE);
+
+        let mut mods = ModifiersState::empty();
+        mods.set(ModifiersState::SHIFT, is_active(xkb::MOD_NAME_SHIFT));
+        mods.set(ModifiersState::CONTROL, is_active(xkb::MOD_NAME_CTRL));
+        mods.set(ModifiersState::ALT, is_active(xkb::MOD_NAME_ALT));
+        mods.set(ModifiersState::SUPER, is_active(xkb::MOD_NAME_LOGO));
+        mods.set(ModifiersState::CAPS_LOCK, is_active(xkb::MOD_NAME_CAPS));
+        mods.set(ModifiersState::NUM_LOCK, is_active(xkb::MOD_NAME_NUM));
+
+        // Unlike Shift/Ctrl/Alt/Super/Caps/Num, "Hyper" has no fixed
+        // `XKB_MOD_NAME_*` constant - it's a virtual modifier some layouts
+        // define and some don't - so its index has to be looked up by name
+        // against this layout's keymap instead.
+        let hyper_index = keymap.mod_get_index("Hyper");
+        if hyper_index != xkb::MOD_INVALID {
+            mods.set(
+                ModifiersState::HYPER,
+                state.mod_index_is_active(hyper_index, xkb::STATE_MODS_EFFECTIVE),
+            );
+        }
+
+        for &keycode in &self.held_side_keys {
+            let keysym = state.key_get_one_sym(keycode + 8);
+            match keysym {
+                xkb::KEY_Shift_L => mods.insert(ModifiersState::LEFT_SHIFT),
+                xkb::KEY_Shift_R => mods.insert(ModifiersState::RIGHT_SHIFT),
+                xkb::KEY_Control_L => mods.insert(ModifiersState::LEFT_CONTROL),
+                xkb::KEY_Control_R => mods.insert(ModifiersState::RIGHT_CONTROL),
+                xkb::KEY_Alt_L => mods.insert(ModifiersState::LEFT_ALT),
+                xkb::KEY_Alt_R => mods.insert(ModifiersState::RIGHT_ALT),
+                xkb::KEY_Super_L => mods.insert(ModifiersState::LEFT_SUPER),
+                xkb::KEY_Super_R => mods.insert(ModifiersState::RIGHT_SUPER),
+                _ => (),
+            }
+        }
+
+        Some(mods)
+    }
+
+    /// Translates an evdev keycode (as delivered by `wl_keyboard::key`) into
+    /// a winit `Key`, `KeyCode`, and `KeyLocation`, honoring whatever layout
+    /// group the currently loaded keymap defines. A dead keysym is reported
+    /// as `Key::Dead`; it is not composed with the key pressed after it.
+    ///
+    /// Returns `None` if no keymap has been loaded yet.
+    pub fn key_to_winit(&mut self, keycode: u32) -> Option<(Key<'static>, KeyCode, KeyLocation)> {
+        let state = self.state.as_mut()?;
+
+        // Evdev keycodes are offset by 8 from the X/xkb keycode space.
+        let xkb_keycode = keycode + 8;
+        let keysym = state.key_get_one_sym(xkb_keycode);
+
+        let native_code = NativeKeyCode::Xkb(xkb_keycode);
+        // `KeyCode`/`KeyLocation` identify the *physical* key, so they're
+        // derived from the fixed evdev scancode rather than `keysym` above -
+        // the keysym reflects whatever the active layout/group and held
+        // modifiers currently produce (e.g. `W` instead of `w` while Shift
+        // is held, or a navigation keysym instead of `KP_7` while NumLock is
+        // off), which would misreport the physical key that was pressed.
+        // This mirrors Windows, which maps the fixed hardware scancode
+        // rather than the live virtual-key symbol.
+        let key_code = evdev_code_to_key_code(keycode);
+        let location = evdev_code_to_location(keycode);
+
+        let key = if xkb::keysym_is_dead(keysym) {
+            Key::Dead(char::from_u32(xkb::keysym_to_utf32(keysym)))
+        } else {
+            let utf8 = state.key_get_utf8(xkb_keycode);
+            if utf8.is_empty() {
+                Key::Unidentified(native_code)
+            } else {
+                Key::Character(get_or_insert_str(&mut self.strings, utf8))
+            }
+        };
+
+        Some((key, key_code, location))
+    }
+}
+
+/// Evdev scancodes (`linux/input-event-codes.h`) for the keys
+/// `evdev_code_to_key_code`/`evdev_code_to_location` classify.
+///
+/// Unlike keysyms, these identify a physical key regardless of the active
+/// layout, group, or held modifiers - the same property Windows gets for
+/// free from its hardware scancodes.
+mod evdev {
+    pub const KEY_ESC: u32 = 1;
+    pub const KEY_1: u32 = 2;
+    pub const KEY_2: u32 = 3;
+    pub const KEY_3: u32 = 4;
+    pub const KEY_4: u32 = 5;
+    pub const KEY_5: u32 = 6;
+    pub const KEY_6: u32 = 7;
+    pub const KEY_7: u32 = 8;
+    pub const KEY_8: u32 = 9;
+    pub const KEY_9: u32 = 10;
+    pub const KEY_0: u32 = 11;
+    pub const KEY_MINUS: u32 = 12;
+    pub const KEY_EQUAL: u32 = 13;
+    pub const KEY_BACKSPACE: u32 = 14;
+    pub const KEY_TAB: u32 = 15;
+    pub const KEY_Q: u32 = 16;
+    pub const KEY_W: u32 = 17;
+    pub const KEY_E: u32 = 18;
+    pub const KEY_R: u32 = 19;
+    pub const KEY_T: u32 = 20;
+    pub const KEY_Y: u32 = 21;
+    pub const KEY_U: u32 = 22;
+    pub const KEY_I: u32 = 23;
+    pub const KEY_O: u32 = 24;
+    pub const KEY_P: u32 = 25;
+    pub const KEY_LEFTBRACE: u32 = 26;
+    pub const KEY_RIGHTBRACE: u32 = 27;
+    pub const KEY_ENTER: u32 = 28;
+    pub const KEY_LEFTCTRL: u32 = 29;
+    pub const KEY_A: u32 = 30;
+    pub const KEY_S: u32 = 31;
+    pub const KEY_D: u32 = 32;
+    pub const KEY_F: u32 = 33;
+    pub const KEY_G: u32 = 34;
+    pub const KEY_H: u32 = 35;
+    pub const KEY_J: u32 = 36;
+    pub const KEY_K: u32 = 37;
+    pub const KEY_L: u32 = 38;
+    pub const KEY_SEMICOLON: u32 = 39;
+    pub const KEY_APOSTROPHE: u32 = 40;
+    pub const KEY_GRAVE: u32 = 41;
+    pub const KEY_LEFTSHIFT: u32 = 42;
+    pub const KEY_BACKSLASH: u32 = 43;
+    pub const KEY_Z: u32 = 44;
+    pub const KEY_X: u32 = 45;
+    pub const KEY_C: u32 = 46;
+    pub const KEY_V: u32 = 47;
+    pub const KEY_B: u32 = 48;
+    pub const KEY_N: u32 = 49;
+    pub const KEY_M: u32 = 50;
+    pub const KEY_COMMA: u32 = 51;
+    pub const KEY_DOT: u32 = 52;
+    pub const KEY_SLASH: u32 = 53;
+    pub const KEY_RIGHTSHIFT: u32 = 54;
+    pub const KEY_KPASTERISK: u32 = 55;
+    pub const KEY_LEFTALT: u32 = 56;
+    pub const KEY_SPACE: u32 = 57;
+    pub const KEY_CAPSLOCK: u32 = 58;
+    pub const KEY_F1: u32 = 59;
+    pub const KEY_F2: u32 = 60;
+    pub const KEY_F3: u32 = 61;
+    pub const KEY_F4: u32 = 62;
+    pub const KEY_F5: u32 = 63;
+    pub const KEY_F6: u32 = 64;
+    pub const KEY_F7: u32 = 65;
+    pub const KEY_F8: u32 = 66;
+    pub const KEY_F9: u32 = 67;
+    pub const KEY_F10: u32 = 68;
+    pub const KEY_NUMLOCK: u32 = 69;
+    pub const KEY_KP7: u32 = 71;
+    pub const KEY_KP8: u32 = 72;
+    pub const KEY_KP9: u32 = 73;
+    pub const KEY_KPMINUS: u32 = 74;
+    pub const KEY_KP4: u32 = 75;
+    pub const KEY_KP5: u32 = 76;
+    pub const KEY_KP6: u32 = 77;
+    pub const KEY_KPPLUS: u32 = 78;
+    pub const KEY_KP1: u32 = 79;
+    pub const KEY_KP2: u32 = 80;
+    pub const KEY_KP3: u32 = 81;
+    pub const KEY_KP0: u32 = 82;
+    pub const KEY_KPDOT: u32 = 83;
+    pub const KEY_102ND: u32 = 86;
+    pub const KEY_F11: u32 = 87;
+    pub const KEY_F12: u32 = 88;
+    pub const KEY_KPENTER: u32 = 96;
+    pub const KEY_RIGHTCTRL: u32 = 97;
+    pub const KEY_KPSLASH: u32 = 98;
+    pub const KEY_RIGHTALT: u32 = 100;
+    pub const KEY_HOME: u32 = 102;
+    pub const KEY_UP: u32 = 103;
+    pub const KEY_PAGEUP: u32 = 104;
+    pub const KEY_LEFT: u32 = 105;
+    pub const KEY_RIGHT: u32 = 106;
+    pub const KEY_END: u32 = 107;
+    pub const KEY_DOWN: u32 = 108;
+    pub const KEY_PAGEDOWN: u32 = 109;
+    pub const KEY_INSERT: u32 = 110;
+    pub const KEY_DELETE: u32 = 111;
+    pub const KEY_LEFTMETA: u32 = 125;
+    pub const KEY_RIGHTMETA: u32 = 126;
+}
+
+/// Translates an evdev scancode into the winit `KeyCode` for the physical
+/// key it identifies.
+///
+/// Following the same free-function pattern the Windows backend uses
+/// (`native_key_to_code`) rather than adding ad hoc constructors to the
+/// shared, platform-agnostic `keyboard.rs` types. This only handles pure
+/// evdev scancode classification, so it would apply identically on X11 if
+/// that backend ever wants to share it.
+fn evdev_code_to_key_code(keycode: u32) -> KeyCode {
+    use evdev::*;
+    match keycode {
+        KEY_A => KeyCode::KeyA,
+        KEY_B => KeyCode::KeyB,
+        KEY_C => KeyCode::KeyC,
+        KEY_D => KeyCode::KeyD,
+        KEY_E => KeyCode::KeyE,
+        KEY_F => KeyCode::KeyF,
+        KEY_G => KeyCode::KeyG,
+        KEY_H => KeyCode::KeyH,
+        KEY_I => KeyCode::KeyI,
+        KEY_J => KeyCode::KeyJ,
+        KEY_K => KeyCode::KeyK,
+        KEY_L => KeyCode::KeyL,
+        KEY_M => KeyCode::KeyM,
+        KEY_N => KeyCode::KeyN,
+        KEY_O => KeyCode::KeyO,
+        KEY_P => KeyCode::KeyP,
+        KEY_Q => KeyCode::KeyQ,
+        KEY_R => KeyCode::KeyR,
+        KEY_S => KeyCode::KeyS,
+        KEY_T => KeyCode::KeyT,
+        KEY_U => KeyCode::KeyU,
+        KEY_V => KeyCode::KeyV,
+        KEY_W => KeyCode::KeyW,
+        KEY_X => KeyCode::KeyX,
+        KEY_Y => KeyCode::KeyY,
+        KEY_Z => KeyCode::KeyZ,
+        KEY_0 => KeyCode::Digit0,
+        KEY_1 => KeyCode::Digit1,
+        KEY_2 => KeyCode::Digit2,
+        KEY_3 => KeyCode::Digit3,
+        KEY_4 => KeyCode::Digit4,
+        KEY_5 => KeyCode::Digit5,
+        KEY_6 => KeyCode::Digit6,
+        KEY_7 => KeyCode::Digit7,
+        KEY_8 => KeyCode::Digit8,
+        KEY_9 => KeyCode::Digit9,
+        KEY_GRAVE => KeyCode::Backquote,
+        KEY_BACKSLASH => KeyCode::Backslash,
+        KEY_LEFTBRACE => KeyCode::BracketLeft,
+        KEY_RIGHTBRACE => KeyCode::BracketRight,
+        KEY_COMMA => KeyCode::Comma,
+        KEY_EQUAL => KeyCode::Equal,
+        KEY_MINUS => KeyCode::Minus,
+        KEY_DOT => KeyCode::Period,
+        KEY_APOSTROPHE => KeyCode::Quote,
+        KEY_SEMICOLON => KeyCode::Semicolon,
+        KEY_SLASH => KeyCode::Slash,
+        KEY_102ND => KeyCode::IntlBackslash,
+        KEY_LEFTSHIFT => KeyCode::ShiftLeft,
+        KEY_RIGHTSHIFT => KeyCode::ShiftRight,
+        KEY_LEFTCTRL => KeyCode::ControlLeft,
+        KEY_RIGHTCTRL => KeyCode::ControlRight,
+        KEY_LEFTALT => KeyCode::AltLeft,
+        KEY_RIGHTALT => KeyCode::AltRight,
+        KEY_LEFTMETA => KeyCode::SuperLeft,
+        KEY_RIGHTMETA => KeyCode::SuperRight,
+        KEY_CAPSLOCK => KeyCode::CapsLock,
+        KEY_NUMLOCK => KeyCode::NumLock,
+        KEY_ENTER => KeyCode::Enter,
+        KEY_TAB => KeyCode::Tab,
+        KEY_SPACE => KeyCode::Space,
+        KEY_BACKSPACE => KeyCode::Backspace,
+        KEY_ESC => KeyCode::Escape,
+        KEY_DELETE => KeyCode::Delete,
+        KEY_INSERT => KeyCode::Insert,
+        KEY_HOME => KeyCode::Home,
+        KEY_END => KeyCode::End,
+        KEY_PAGEUP => KeyCode::PageUp,
+        KEY_PAGEDOWN => KeyCode::PageDown,
+        KEY_LEFT => KeyCode::ArrowLeft,
+        KEY_RIGHT => KeyCode::ArrowRight,
+        KEY_UP => KeyCode::ArrowUp,
+        KEY_DOWN => KeyCode::ArrowDown,
+        KEY_F1 => KeyCode::F1,
+        KEY_F2 => KeyCode::F2,
+        KEY_F3 => KeyCode::F3,
+        KEY_F4 => KeyCode::F4,
+        KEY_F5 => KeyCode::F5,
+        KEY_F6 => KeyCode::F6,
+        KEY_F7 => KeyCode::F7,
+        KEY_F8 => KeyCode::F8,
+        KEY_F9 => KeyCode::F9,
+        KEY_F10 => KeyCode::F10,
+        KEY_F11 => KeyCode::F11,
+        KEY_F12 => KeyCode::F12,
+        KEY_KP0 => KeyCode::Numpad0,
+        KEY_KP1 => KeyCode::Numpad1,
+        KEY_KP2 => KeyCode::Numpad2,
+        KEY_KP3 => KeyCode::Numpad3,
+        KEY_KP4 => KeyCode::Numpad4,
+        KEY_KP5 => KeyCode::Numpad5,
+        KEY_KP6 => KeyCode::Numpad6,
+        KEY_KP7 => KeyCode::Numpad7,
+        KEY_KP8 => KeyCode::Numpad8,
+        KEY_KP9 => KeyCode::Numpad9,
+        KEY_KPPLUS => KeyCode::NumpadAdd,
+        KEY_KPMINUS => KeyCode::NumpadSubtract,
+        KEY_KPASTERISK => KeyCode::NumpadMultiply,
+        KEY_KPSLASH => KeyCode::NumpadDivide,
+        KEY_KPDOT => KeyCode::NumpadDecimal,
+        KEY_KPENTER => KeyCode::NumpadEnter,
+        _ => KeyCode::Unidentified,
+    }
+}
+
+/// Classifies an evdev scancode as `Left`/`Right`/`Numpad`/`Standard`. The
+/// `Left`/`Right` part is the distinction `ModifierKeymap::get_side` draws
+/// for X11 from a keysym; this works from the fixed scancode instead, so
+/// it doesn't depend on NumLock -
+/// with NumLock off, a numpad key's keysym is a navigation keysym
+/// (`Home`, `Up`, ...), not a `KP_*` one, and would otherwise misclassify
+/// the key as `Standard`.
+fn evdev_code_to_location(keycode: u32) -> KeyLocation {
+    use evdev::*;
+    match keycode {
+        KEY_LEFTSHIFT | KEY_LEFTCTRL | KEY_LEFTALT | KEY_LEFTMETA => KeyLocation::Left,
+        KEY_RIGHTSHIFT | KEY_RIGHTCTRL | KEY_RIGHTALT | KEY_RIGHTMETA => KeyLocation::Right,
+        KEY_KP0 | KEY_KP1 | KEY_KP2 | KEY_KP3 | KEY_KP4 | KEY_KP5 | KEY_KP6 | KEY_KP7 | KEY_KP8
+        | KEY_KP9 | KEY_KPENTER | KEY_KPPLUS | KEY_KPMINUS | KEY_KPASTERISK | KEY_KPSLASH
+        | KEY_KPDOT => KeyLocation::Numpad,
+        _ => KeyLocation::Standard,
+    }
+}
+
+/// Interns `string`, returning a leaked `&'static str` deduplicated against
+/// every string interned through this set so far.
+///
+/// Mirrors the leak-and-dedup pattern the Windows layout cache uses for its
+/// `Key::Character` labels: both backends need a `'static` string to hand
+/// back from a `Key` without re-allocating on every lookup, and both only
+/// ever see a small, bounded set of distinct key labels per layout.
+fn get_or_insert_str(strings: &mut HashSet<&'static str>, string: String) -> &'static str {
+    {
+        let str_ref = string.as_str();
+        if let Some(&existing) = strings.get(str_ref) {
+            return existing;
+        }
+    }
+    let leaked = Box::leak(Box::from(string));
+    strings.insert(leaked);
+    leaked
+}
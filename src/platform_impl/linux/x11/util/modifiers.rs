@@ -1,21 +1,20 @@
-use std::{collections::HashMap, slice};
+use std::{collections::HashMap, os::raw::c_uint, slice};
 
 use super::*;
 
 use crate::event::ElementState;
 use crate::keyboard::ModifiersState;
 
-// Offsets within XModifierKeymap to each set of keycodes.
-// We are only interested in Shift, Control, Alt, and Logo.
+// There are 8 modifier keycode sets in `XModifierKeymap`, in the order:
+//     Shift, Lock, Control, Mod1, Mod2, Mod3, Mod4, Mod5
 //
-// There are 8 sets total. The order of keycode sets is:
-//     Shift, Lock, Control, Mod1 (Alt), Mod2, Mod3, Mod4 (Logo), Mod5
+// Which of Mod1-Mod5 carries Alt, Logo, AltGr (Mode_switch/ISO_Level3_Shift)
+// or NumLock isn't fixed by the protocol - it depends on how the server's
+// XKB config assigned them - so rather than assume e.g. "Alt is always
+// Mod1", we resolve every keycode in every column to its keysym and
+// classify it from there, the way Wine's keyboard driver does this.
 //
 // https://tronche.com/gui/x/xlib/input/XSetModifierMapping.html
-const SHIFT_OFFSET: usize = 0;
-const CONTROL_OFFSET: usize = 2;
-const ALT_OFFSET: usize = 3;
-const LOGO_OFFSET: usize = 6;
 const NUM_MODS: usize = 8;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -24,12 +23,30 @@ pub enum Modifier {
     Ctrl,
     Shift,
     Logo,
+    CapsLock,
+    NumLock,
+}
+
+/// Which physical half of a side-aware modifier (Shift, Ctrl, Alt, Logo) a
+/// keycode belongs to, resolved from its keysym.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
 }
 
 #[derive(Debug, Default)]
 pub struct ModifierKeymap {
     // Maps keycodes to modifiers
     keys: HashMap<ffi::KeyCode, Modifier>,
+    // Maps keycodes to which side of the modifier they're on, for the
+    // modifiers that have a left/right distinction.
+    sides: HashMap<ffi::KeyCode, Side>,
+    // Mask bit (as used in `XKeyEvent::state`) of whichever Mod column this
+    // layout put AltGr (Mode_switch/ISO_Level3_Shift) on, if any.
+    alt_gr_mask: Option<c_uint>,
+    // Mask bit of whichever Mod column this layout put NumLock on, if any.
+    num_lock_mask: Option<c_uint>,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -47,6 +64,33 @@ impl ModifierKeymap {
         self.keys.get(&keycode).cloned()
     }
 
+    /// Returns which side of a modifier `keycode` is on, if it's one of the
+    /// keys we can tell `Left` from `Right` for.
+    pub fn get_side(&self, keycode: ffi::KeyCode) -> Option<Side> {
+        self.sides.get(&keycode).cloned()
+    }
+
+    /// The mask bit of `XKeyEvent::state` that's set while AltGr
+    /// (Mode_switch/ISO_Level3_Shift) is held, if this layout has one.
+    ///
+    /// This is purely descriptive, and deliberately has no `filter_out_altgr`
+    /// counterpart the way the Windows `WindowsModifiers` masking does. On
+    /// Windows, AltGr is synthesized as a simultaneous Ctrl+Alt VK press, so
+    /// that phantom Ctrl+Alt has to be filtered back out of the reported
+    /// modifiers. XKB instead puts AltGr on its own Mod column, and
+    /// `XKeyEvent::state` only ever reflects keys that are actually
+    /// physically held - there's no phantom Ctrl+Alt here to strip, so no
+    /// masking step is needed on X11 and none should be added back.
+    pub fn alt_gr_mask(&self) -> Option<c_uint> {
+        self.alt_gr_mask
+    }
+
+    /// The mask bit of `XKeyEvent::state` that's set while NumLock is
+    /// engaged, if this layout has one.
+    pub fn num_lock_mask(&self) -> Option<c_uint> {
+        self.num_lock_mask
+    }
+
     pub fn reset_from_x_connection(&mut self, xconn: &XConnection) {
         unsafe {
             let keymap = (xconn.xlib.XGetModifierMapping)(xconn.display);
@@ -55,13 +99,13 @@ impl ModifierKeymap {
                 panic!("failed to allocate XModifierKeymap");
             }
 
-            self.reset_from_x_keymap(&*keymap);
+            self.reset_from_x_keymap(xconn, &*keymap);
 
             (xconn.xlib.XFreeModifiermap)(keymap);
         }
     }
 
-    fn reset_from_x_keymap(&mut self, keymap: &ffi::XModifierKeymap) {
+    fn reset_from_x_keymap(&mut self, xconn: &XConnection, keymap: &ffi::XModifierKeymap) {
         let keys_per_mod = keymap.max_keypermod as usize;
 
         let keys = unsafe {
@@ -69,29 +113,56 @@ impl ModifierKeymap {
         };
 
         self.keys.clear();
+        self.sides.clear();
+        self.alt_gr_mask = None;
+        self.num_lock_mask = None;
 
-        self.read_x_keys(keys, SHIFT_OFFSET, keys_per_mod, Modifier::Shift);
-        self.read_x_keys(keys, CONTROL_OFFSET, keys_per_mod, Modifier::Ctrl);
-        self.read_x_keys(keys, ALT_OFFSET, keys_per_mod, Modifier::Alt);
-        self.read_x_keys(keys, LOGO_OFFSET, keys_per_mod, Modifier::Logo);
-    }
+        for column in 0..NUM_MODS {
+            let mask = 1 << column;
+            let start = column * keys_per_mod;
+            let end = start + keys_per_mod;
 
-    fn read_x_keys(
-        &mut self,
-        keys: &[ffi::KeyCode],
-        offset: usize,
-        keys_per_mod: usize,
-        modifier: Modifier,
-    ) {
-        let start = offset * keys_per_mod;
-        let end = start + keys_per_mod;
-
-        for &keycode in &keys[start..end] {
-            if keycode != 0 {
-                self.keys.insert(keycode, modifier);
+            for &keycode in &keys[start..end] {
+                if keycode == 0 {
+                    continue;
+                }
+
+                let keysym = unsafe { (xconn.xlib.XKeycodeToKeysym)(xconn.display, keycode, 0) }
+                    as ffi::KeySym;
+
+                match keysym {
+                    ffi::XK_Shift_L => self.set_side(keycode, Modifier::Shift, Side::Left),
+                    ffi::XK_Shift_R => self.set_side(keycode, Modifier::Shift, Side::Right),
+                    ffi::XK_Control_L => self.set_side(keycode, Modifier::Ctrl, Side::Left),
+                    ffi::XK_Control_R => self.set_side(keycode, Modifier::Ctrl, Side::Right),
+                    ffi::XK_Alt_L => self.set_side(keycode, Modifier::Alt, Side::Left),
+                    ffi::XK_Alt_R => self.set_side(keycode, Modifier::Alt, Side::Right),
+                    ffi::XK_Super_L | ffi::XK_Meta_L => {
+                        self.set_side(keycode, Modifier::Logo, Side::Left)
+                    }
+                    ffi::XK_Super_R | ffi::XK_Meta_R => {
+                        self.set_side(keycode, Modifier::Logo, Side::Right)
+                    }
+                    ffi::XK_Caps_Lock => {
+                        self.keys.insert(keycode, Modifier::CapsLock);
+                    }
+                    ffi::XK_Num_Lock => {
+                        self.keys.insert(keycode, Modifier::NumLock);
+                        self.num_lock_mask.get_or_insert(mask);
+                    }
+                    ffi::XK_Mode_switch | ffi::XK_ISO_Level3_Shift => {
+                        self.alt_gr_mask.get_or_insert(mask);
+                    }
+                    _ => (),
+                }
             }
         }
     }
+
+    fn set_side(&mut self, keycode: ffi::KeyCode, modifier: Modifier, side: Side) {
+        self.keys.insert(keycode, modifier);
+        self.sides.insert(keycode, side);
+    }
 }
 
 impl ModifierKeyState {
@@ -103,12 +174,32 @@ impl ModifierKeyState {
         let mut new_state = *state;
 
         match except {
-            Some(Modifier::Alt) => new_state.set(ModifiersState::ALT, self.state.alt_key()),
+            Some(Modifier::Alt) => {
+                new_state.set(ModifiersState::ALT, self.state.alt_key());
+                new_state.set(ModifiersState::LEFT_ALT, self.state.left_alt_key());
+                new_state.set(ModifiersState::RIGHT_ALT, self.state.right_alt_key());
+            }
             Some(Modifier::Ctrl) => {
-                new_state.set(ModifiersState::CONTROL, self.state.control_key())
+                new_state.set(ModifiersState::CONTROL, self.state.control_key());
+                new_state.set(ModifiersState::LEFT_CONTROL, self.state.left_control_key());
+                new_state.set(ModifiersState::RIGHT_CONTROL, self.state.right_control_key());
+            }
+            Some(Modifier::Shift) => {
+                new_state.set(ModifiersState::SHIFT, self.state.shift_key());
+                new_state.set(ModifiersState::LEFT_SHIFT, self.state.left_shift_key());
+                new_state.set(ModifiersState::RIGHT_SHIFT, self.state.right_shift_key());
+            }
+            Some(Modifier::Logo) => {
+                new_state.set(ModifiersState::SUPER, self.state.super_key());
+                new_state.set(ModifiersState::LEFT_SUPER, self.state.left_super_key());
+                new_state.set(ModifiersState::RIGHT_SUPER, self.state.right_super_key());
+            }
+            Some(Modifier::CapsLock) => {
+                new_state.set(ModifiersState::CAPS_LOCK, self.state.caps_lock_key())
+            }
+            Some(Modifier::NumLock) => {
+                new_state.set(ModifiersState::NUM_LOCK, self.state.num_lock_key())
             }
-            Some(Modifier::Shift) => new_state.set(ModifiersState::SHIFT, self.state.shift_key()),
-            Some(Modifier::Logo) => new_state.set(ModifiersState::SUPER, self.state.super_key()),
             None => (),
         }
 
@@ -124,19 +215,39 @@ impl ModifierKeyState {
         self.state
     }
 
-    pub fn key_event(&mut self, state: ElementState, modifier: Modifier) {
-        match state {
-            ElementState::Pressed => self.key_press(modifier),
-            ElementState::Released => self.key_release(modifier),
+    pub fn key_event(&mut self, state: ElementState, modifier: Modifier, side: Option<Side>) {
+        match modifier {
+            // Caps lock and num lock are toggled by their key being pressed,
+            // not held, so only react to the press half of the event.
+            Modifier::CapsLock | Modifier::NumLock => {
+                if state == ElementState::Pressed {
+                    self.toggle_modifier(modifier);
+                }
+            }
+            _ => match state {
+                ElementState::Pressed => self.key_press(modifier, side),
+                ElementState::Released => self.key_release(modifier, side),
+            },
         }
     }
 
-    fn key_press(&mut self, modifier: Modifier) {
-        set_modifier(&mut self.state, modifier, true);
+    fn key_press(&mut self, modifier: Modifier, side: Option<Side>) {
+        set_side_modifier(&mut self.state, modifier, side, true);
+        set_modifier_from_sides(&mut self.state, modifier);
+    }
+
+    fn key_release(&mut self, modifier: Modifier, side: Option<Side>) {
+        set_side_modifier(&mut self.state, modifier, side, false);
+        set_modifier_from_sides(&mut self.state, modifier);
     }
 
-    fn key_release(&mut self, modifier: Modifier) {
-        set_modifier(&mut self.state, modifier, false);
+    fn toggle_modifier(&mut self, modifier: Modifier) {
+        let is_active = match modifier {
+            Modifier::CapsLock => self.state.caps_lock_key(),
+            Modifier::NumLock => self.state.num_lock_key(),
+            _ => return,
+        };
+        set_modifier(&mut self.state, modifier, !is_active);
     }
 }
 
@@ -146,5 +257,43 @@ fn set_modifier(state: &mut ModifiersState, modifier: Modifier, value: bool) {
         Modifier::Ctrl => state.set(ModifiersState::CONTROL, value),
         Modifier::Shift => state.set(ModifiersState::SHIFT, value),
         Modifier::Logo => state.set(ModifiersState::SUPER, value),
+        Modifier::CapsLock => state.set(ModifiersState::CAPS_LOCK, value),
+        Modifier::NumLock => state.set(ModifiersState::NUM_LOCK, value),
+    }
+}
+
+/// Recomputes the unified flag (`SHIFT`, `CONTROL`, `ALT` or `SUPER`) for a
+/// side-aware modifier from the OR of its two side flags, so that releasing
+/// one side while the other is still held doesn't clear the unified flag.
+fn set_modifier_from_sides(state: &mut ModifiersState, modifier: Modifier) {
+    let value = match modifier {
+        Modifier::Shift => state.left_shift_key() || state.right_shift_key(),
+        Modifier::Ctrl => state.left_control_key() || state.right_control_key(),
+        Modifier::Alt => state.left_alt_key() || state.right_alt_key(),
+        Modifier::Logo => state.left_super_key() || state.right_super_key(),
+        Modifier::CapsLock | Modifier::NumLock => return,
+    };
+    set_modifier(state, modifier, value);
+}
+
+fn set_side_modifier(
+    state: &mut ModifiersState,
+    modifier: Modifier,
+    side: Option<Side>,
+    value: bool,
+) {
+    let flag = match (modifier, side) {
+        (Modifier::Shift, Some(Side::Left)) => Some(ModifiersState::LEFT_SHIFT),
+        (Modifier::Shift, Some(Side::Right)) => Some(ModifiersState::RIGHT_SHIFT),
+        (Modifier::Ctrl, Some(Side::Left)) => Some(ModifiersState::LEFT_CONTROL),
+        (Modifier::Ctrl, Some(Side::Right)) => Some(ModifiersState::RIGHT_CONTROL),
+        (Modifier::Alt, Some(Side::Left)) => Some(ModifiersState::LEFT_ALT),
+        (Modifier::Alt, Some(Side::Right)) => Some(ModifiersState::RIGHT_ALT),
+        (Modifier::Logo, Some(Side::Left)) => Some(ModifiersState::LEFT_SUPER),
+        (Modifier::Logo, Some(Side::Right)) => Some(ModifiersState::RIGHT_SUPER),
+        _ => None,
+    };
+    if let Some(flag) = flag {
+        state.set(flag, value);
     }
 }
@@ -22,6 +22,10 @@ fn key_pressed(vkey: c_int) -> bool {
     unsafe { (winuser::GetKeyState(vkey) & (1 << 15)) == (1 << 15) }
 }
 
+fn key_toggled(vkey: c_int) -> bool {
+    unsafe { (winuser::GetKeyState(vkey) & 1) != 0 }
+}
+
 bitflags! {
     pub struct WindowsModifiers : u8 {
         const SHIFT = 1 << 0;
@@ -32,6 +36,13 @@ bitflags! {
     }
 }
 
+/// Number of distinct `WindowsModifiers` combinations.
+const NUM_MOD_COMBOS: usize = WindowsModifiers::FLAGS_END.bits as usize;
+/// Upper bound on `KeyCode`'s discriminants. `KeyCode` is a C-style enum, so
+/// this only needs to be large enough to hold every variant; `Layout::key_index`
+/// debug-asserts that it actually is.
+const NUM_KEYCODES: usize = 256;
+
 impl WindowsModifiers {
     pub fn active_modifiers(key_state: &[u8; 256]) -> WindowsModifiers {
         let shift = key_state[winuser::VK_SHIFT as usize] & 0x80 != 0;
@@ -108,39 +119,60 @@ impl WindowsModifiers {
 }
 
 pub struct Layout {
-    /// Maps a modifier state to group of key strings
+    /// Maps a `(modifier state, key code)` pair to a key label.
     /// Not using `ModifiersState` here because that object cannot express caps lock
     /// but we need to handle caps lock too.
     ///
-    /// This map shouldn't need to exist.
+    /// This table shouldn't need to exist.
     /// However currently this seems to be the only good way
     /// of getting the label for the pressed key. Note that calling `ToUnicode`
     /// just when the key is pressed/released would be enough if `ToUnicode` wouldn't
     /// change the keyboard state (it clears the dead key). There is a flag to prevent
     /// changing the state but that flag requires Windows 10, version 1607 or newer)
-    pub keys: HashMap<WindowsModifiers, HashMap<KeyCode, Key<'static>>>,
+    ///
+    /// Flattened into a single array indexed by `mods.bits() * NUM_KEYCODES +
+    /// keycode as usize` instead of a `HashMap<WindowsModifiers, HashMap<KeyCode, _>>`,
+    /// since there are only `NUM_MOD_COMBOS` modifier combinations and
+    /// `KeyCode` is a bounded enum - this turns every lookup into a single
+    /// array index instead of two hashes, and the whole layout into one
+    /// allocation instead of dozens of tiny ones.
+    keys: Box<[Option<Key<'static>>]>,
     pub has_alt_graph: bool,
+    /// Maps a dead key character together with the character of the key
+    /// pressed right after it to the character the two compose into, e.g.
+    /// `('´', 'e')` -> `'é'` on a layout with a dead acute accent key.
+    ///
+    /// Built once, up front, in `prepare_layout` by replaying each dead key
+    /// against every other printable key the layout produces, since
+    /// `ToUnicodeEx` can only tell us the composition by actually performing
+    /// it.
+    pub dead_keys: HashMap<(char, char), char>,
 }
 
 impl Layout {
+    fn key_index(mods: WindowsModifiers, keycode: KeyCode) -> usize {
+        let keycode = keycode as usize;
+        debug_assert!(keycode < NUM_KEYCODES, "KeyCode discriminant out of bounds for NUM_KEYCODES");
+        mods.bits() as usize * NUM_KEYCODES + keycode
+    }
+
     pub fn get_key(
         &self,
         mods: WindowsModifiers,
         scancode: ExScancode,
         keycode: KeyCode,
     ) -> Key<'static> {
-        // let ctrl_alt: WindowsModifiers = WindowsModifiers::CONTROL | WindowsModifiers::ALT;
-        // if self.has_alt_graph && mods.contains(ctrl_alt) {
-
-        // }
-
-        if let Some(keys) = self.keys.get(&mods) {
-            if let Some(key) = keys.get(&keycode) {
-                return *key;
-            }
+        if let Some(Some(key)) = self.keys.get(Self::key_index(mods, keycode)) {
+            return *key;
         }
         Key::Unidentified(NativeKeyCode::Windows(scancode))
     }
+
+    /// Looks up the character produced by typing `base_char` right after the
+    /// dead key that produced `dead_char`, if this layout defines one.
+    pub fn get_dead_key_combination(&self, dead_char: char, base_char: char) -> Option<char> {
+        self.dead_keys.get(&(dead_char, base_char)).copied()
+    }
 }
 
 #[derive(Default)]
@@ -179,27 +211,53 @@ impl LayoutCache {
             key_pressed(winuser::VK_MENU) && !filter_out_altgr,
         );
         mods.set(
-            ModifiersState::META,
+            ModifiersState::SUPER,
             key_pressed(winuser::VK_LWIN) || key_pressed(winuser::VK_RWIN),
         );
+        mods.set(ModifiersState::CAPS_LOCK, key_toggled(winuser::VK_CAPITAL));
+        mods.set(ModifiersState::NUM_LOCK, key_toggled(winuser::VK_NUMLOCK));
+
+        // Side-specific flags reflect which physical key is held regardless
+        // of the AltGr filtering above, since that logic only concerns
+        // itself with whether to fold AltGr into the unified Ctrl/Alt flags.
+        mods.set(ModifiersState::LEFT_SHIFT, key_pressed(winuser::VK_LSHIFT));
+        mods.set(ModifiersState::RIGHT_SHIFT, key_pressed(winuser::VK_RSHIFT));
+        mods.set(
+            ModifiersState::LEFT_CONTROL,
+            key_pressed(winuser::VK_LCONTROL),
+        );
+        mods.set(
+            ModifiersState::RIGHT_CONTROL,
+            key_pressed(winuser::VK_RCONTROL),
+        );
+        mods.set(ModifiersState::LEFT_ALT, key_pressed(winuser::VK_LMENU));
+        mods.set(ModifiersState::RIGHT_ALT, key_pressed(winuser::VK_RMENU));
+        mods.set(ModifiersState::LEFT_SUPER, key_pressed(winuser::VK_LWIN));
+        mods.set(ModifiersState::RIGHT_SUPER, key_pressed(winuser::VK_RWIN));
         mods
     }
 
     fn prepare_layout(strings: &mut HashSet<&'static str>, locale_id: u64) -> Layout {
         let mut layout = Layout {
-            keys: Default::default(),
+            keys: vec![None; NUM_MOD_COMBOS * NUM_KEYCODES].into_boxed_slice(),
             has_alt_graph: false,
+            dead_keys: Default::default(),
         };
 
         // We initialize the keyboard state with all zeros to
         // simulate a scenario when no modifier is active.
         let mut key_state = [0u8; 256];
 
+        // The (vk, scancode, modifiers) of the first key we find that
+        // produces each dead character / printable character, so we can
+        // replay them below, under the same modifiers, to discover the
+        // layout's dead-key compositions.
+        let mut dead_key_sources: HashMap<char, (u32, u32, WindowsModifiers)> = HashMap::new();
+        let mut base_key_sources: HashMap<char, (u32, u32, WindowsModifiers)> = HashMap::new();
+
         // Iterate through every combination of modifiers
         let mods_end = WindowsModifiers::FLAGS_END.bits;
         for mod_state in 0..mods_end {
-            let mut keys_for_this_mod = HashMap::with_capacity(256);
-
             let mod_state = unsafe { WindowsModifiers::from_bits_unchecked(mod_state) };
             mod_state.apply_to_kbd_state(&mut key_state);
 
@@ -226,7 +284,8 @@ impl LayoutCache {
                 match preliminary_key {
                     Key::Unidentified(_) => (),
                     _ => {
-                        keys_for_this_mod.insert(key_code, preliminary_key);
+                        layout.keys[Layout::key_index(mod_state, key_code)] =
+                            Some(preliminary_key);
                         continue;
                     }
                 }
@@ -234,11 +293,21 @@ impl LayoutCache {
                 let unicode = Self::to_unicode_string(&key_state, vk, scancode, locale_id);
                 let key = match unicode {
                     ToUnicodeResult::Str(str) => {
+                        if let Some(base_char) = single_char(&str) {
+                            base_key_sources
+                                .entry(base_char)
+                                .or_insert((vk, scancode, mod_state));
+                        }
                         let static_str = get_or_insert_str(strings, str);
                         Key::Character(static_str)
                     }
                     ToUnicodeResult::Dead(dead_char) => {
                         //println!("{:?} - {:?} produced dead {:?}", key_code, mod_state, dead_char);
+                        if let Some(dead_char) = dead_char {
+                            dead_key_sources
+                                .entry(dead_char)
+                                .or_insert((vk, scancode, mod_state));
+                        }
                         Key::Dead(dead_char)
                     }
                     ToUnicodeResult::None => {
@@ -262,28 +331,71 @@ impl LayoutCache {
                 let ctrl_alt: WindowsModifiers = WindowsModifiers::CONTROL | WindowsModifiers::ALT;
                 let is_in_ctrl_alt = mod_state == ctrl_alt;
                 if !layout.has_alt_graph && is_in_ctrl_alt {
-                    // Unwrapping here because if we are in the ctrl+alt modifier state
+                    // Indexing here directly because if we are in the ctrl+alt modifier state
                     // then the alt modifier state must have come before.
-                    let simple_keys = layout.keys.get(&WindowsModifiers::empty()).unwrap();
-                    if let Some(Key::Character(key_no_altgr)) = simple_keys.get(&key_code) {
+                    let simple_key =
+                        layout.keys[Layout::key_index(WindowsModifiers::empty(), key_code)];
+                    if let Some(Key::Character(key_no_altgr)) = simple_key {
                         if let Key::Character(key) = key {
-                            layout.has_alt_graph = key != *key_no_altgr;
+                            layout.has_alt_graph = key != key_no_altgr;
                         }
                     }
                 }
 
-                keys_for_this_mod.insert(key_code, key);
+                layout.keys[Layout::key_index(mod_state, key_code)] = Some(key);
             }
-            layout.keys.insert(mod_state, keys_for_this_mod);
         }
 
         // Second pass: replace right alt keys with AltGr if the layout has alt graph
         if layout.has_alt_graph {
             for mod_state in 0..mods_end {
                 let mod_state = unsafe { WindowsModifiers::from_bits_unchecked(mod_state) };
-                if let Some(keys) = layout.keys.get_mut(&mod_state) {
-                    if let Some(key) = keys.get_mut(&KeyCode::AltRight) {
-                        *key = Key::AltGraph;
+                let index = Layout::key_index(mod_state, KeyCode::AltRight);
+                if layout.keys[index].is_some() {
+                    layout.keys[index] = Some(Key::AltGraph);
+                }
+            }
+        }
+
+        // Third pass: discover dead-key compositions by replaying each dead
+        // key found above followed by every other printable key, each under
+        // the same modifiers that originally produced it - a dead key or
+        // base character that needed Shift to type (e.g. a shifted dead
+        // acute, or an uppercase base letter) must be replayed with Shift
+        // held, or the composition table ends up keyed under the wrong
+        // (unshifted) character - against scratch keyboard states so the
+        // real `key_state` above is untouched.
+        for (&dead_char, &(dead_vk, dead_scancode, dead_mods)) in &dead_key_sources {
+            for (&base_char, &(base_vk, base_scancode, base_mods)) in &base_key_sources {
+                let mut dead_state = [0u8; 256];
+                dead_mods.apply_to_kbd_state(&mut dead_state);
+                // Priming call: puts the dead key into the thread's pending
+                // dead-key state without going through the consuming
+                // behavior of `to_unicode_string`.
+                unsafe {
+                    let mut discard = [0u16; 8];
+                    winuser::ToUnicodeEx(
+                        dead_vk,
+                        dead_scancode,
+                        (&dead_state[0]) as *const _,
+                        (&mut discard[0]) as *mut _,
+                        discard.len() as i32,
+                        0,
+                        locale_id as HKL,
+                    );
+                }
+
+                let mut base_state = [0u8; 256];
+                base_mods.apply_to_kbd_state(&mut base_state);
+                if let ToUnicodeResult::Str(composed) =
+                    Self::to_unicode_string(&base_state, base_vk, base_scancode, locale_id)
+                {
+                    if let Some(composite_char) = single_char(&composed) {
+                        if composite_char != base_char {
+                            layout
+                                .dead_keys
+                                .insert((dead_char, base_char), composite_char);
+                        }
                     }
                 }
             }
@@ -341,6 +453,120 @@ impl LayoutCache {
     }
 }
 
+/// Keeps the small amount of state the keyboard processor needs to turn a
+/// dead key press and the keystroke right after it into a single composed
+/// character.
+///
+/// A dead key (e.g. a dead acute accent) doesn't produce a character on its
+/// own; it modifies whatever key is pressed next. This holds that pending
+/// dead key between the two events and looks the composition up through
+/// `Layout::get_dead_key_combination`, so the keyboard processor can replace
+/// the second event's key with the composite character instead of emitting
+/// the dead key and the base character as two separate, uncomposed events.
+#[derive(Default)]
+pub struct DeadKeyState {
+    pending: Option<char>,
+}
+
+/// The key(s) produced by feeding one event through [`DeadKeyState::process`].
+pub enum ProcessedKey<'a> {
+    /// `key` was itself a dead key; it's now stashed and there's nothing to
+    /// report until the next key arrives.
+    None,
+    /// `key` unchanged, because no dead key was pending or `key` is not a
+    /// `Key::Character` (a pending dead key is then discarded), or the
+    /// composite character in place of `key`, because a pending dead key
+    /// composed with it.
+    One(Key<'a>),
+    /// A dead key was pending and `key` is a `Key::Character` that didn't
+    /// compose with it, so both have to be reported to match OS behavior:
+    /// the pending dead key's own character, then `key` unchanged.
+    Two(Key<'a>, Key<'a>),
+}
+
+impl DeadKeyState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one resolved `Key` through the pending dead-key state.
+    pub fn process(
+        &mut self,
+        layout: &Layout,
+        strings: &mut HashSet<&'static str>,
+        key: Key<'static>,
+    ) -> ProcessedKey<'static> {
+        if let Key::Dead(dead_char) = key {
+            self.pending = dead_char;
+            return ProcessedKey::None;
+        }
+
+        match (self.pending.take(), key) {
+            (Some(dead_char), Key::Character(base_str)) => {
+                match single_char(base_str).and_then(|base_char| {
+                    layout.get_dead_key_combination(dead_char, base_char)
+                }) {
+                    Some(composite) => ProcessedKey::One(Key::Character(get_or_insert_str(
+                        strings,
+                        composite.to_string(),
+                    ))),
+                    // The pair doesn't compose; fall back to emitting the
+                    // dead character followed by the base character,
+                    // matching what the OS itself does on a miss.
+                    None => ProcessedKey::Two(
+                        Key::Character(get_or_insert_str(strings, dead_char.to_string())),
+                        key,
+                    ),
+                }
+            }
+            (_, key) => ProcessedKey::One(key),
+        }
+    }
+}
+
+/// Buffers a lone UTF-16 high surrogate from `WM_CHAR` input until its
+/// matching low surrogate arrives, so that supplementary-plane characters
+/// (emoji, many CJK extensions) - which Windows always delivers as two
+/// separate `WM_CHAR` messages - get reassembled into a single `Key::Character`
+/// instead of being mangled or dropped one code unit at a time.
+#[derive(Default)]
+pub struct SurrogatePairBuffer {
+    surrogate_buffer: Option<u16>,
+}
+
+impl SurrogatePairBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds one UTF-16 code unit received from a `WM_CHAR` message.
+    ///
+    /// Returns the composed character string once it's complete. Returns
+    /// `None` if `unit` is a high surrogate that's now buffered waiting for
+    /// its low surrogate.
+    pub fn feed(&mut self, unit: u16) -> Option<String> {
+        match (self.surrogate_buffer.take(), unit) {
+            (None, 0xD800..=0xDBFF) => {
+                self.surrogate_buffer = Some(unit);
+                None
+            }
+            (Some(high), 0xDC00..=0xDFFF) => OsString::from_wide(&[high, unit]).into_string().ok(),
+            // The buffered high surrogate wasn't followed by its low
+            // surrogate; discard it and process `unit` on its own instead of
+            // silently dropping it.
+            (Some(_), _) => self.feed(unit),
+            (None, _) => OsString::from_wide(&[unit]).into_string().ok(),
+        }
+    }
+}
+
+/// Returns `Some` if `s` consists of exactly one `char`.
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    let first = chars.next()?;
+    chars.next().is_none().then(|| first)
+}
+
 pub fn get_or_insert_str(strings: &mut HashSet<&'static str>, string: String) -> &'static str {
     {
         let str_ref = string.as_str();
@@ -358,4 +584,39 @@ enum ToUnicodeResult {
     Str(String),
     Dead(Option<char>),
     None,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SurrogatePairBuffer;
+
+    // High/low surrogate pair for U+1F4E6 PACKAGE.
+    const HIGH_SURROGATE: u16 = 0xD83D;
+    const LOW_SURROGATE: u16 = 0xDCE6;
+
+    #[test]
+    fn complete_pair_composes_into_one_character() {
+        let mut buf = SurrogatePairBuffer::new();
+        assert_eq!(buf.feed(HIGH_SURROGATE), None);
+        assert_eq!(buf.feed(LOW_SURROGATE), Some("\u{1F4E6}".to_string()));
+    }
+
+    #[test]
+    fn lone_high_surrogate_is_buffered_rather_than_emitted() {
+        let mut buf = SurrogatePairBuffer::new();
+        assert_eq!(buf.feed(HIGH_SURROGATE), None);
+    }
+
+    #[test]
+    fn lone_low_surrogate_with_nothing_buffered_is_discarded() {
+        let mut buf = SurrogatePairBuffer::new();
+        assert_eq!(buf.feed(LOW_SURROGATE), None);
+    }
+
+    #[test]
+    fn interrupted_pair_discards_the_stale_surrogate_and_processes_the_new_unit() {
+        let mut buf = SurrogatePairBuffer::new();
+        assert_eq!(buf.feed(HIGH_SURROGATE), None);
+        assert_eq!(buf.feed('A' as u16), Some("A".to_string()));
+    }
 }
\ No newline at end of file